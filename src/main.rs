@@ -1,12 +1,15 @@
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitCode};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use anyhow::{anyhow, bail, Context, Error, Result};
 use clap::{Args, Parser, Subcommand};
 use glob::glob;
 use regex::Regex;
 use semver::Version;
+use serde_json::Value;
 
 /// Pre-commit hook for running cargo fmt/check/clippy against a repo.
 /// The target repo may contain multiple independent cargo projects or workspaces.
@@ -37,6 +40,81 @@ struct CargoOpts {
     /// Override the error message printed if `cargo` or the command executable is not found.
     #[clap(long, global = true)]
     not_found_message: Option<String>,
+    /// Pin a specific rustup toolchain (e.g. `nightly`) by prepending `+<toolchain>` to cargo
+    /// invocations, instead of relying on whatever toolchain is active by default.
+    #[clap(long, global = true)]
+    toolchain: Option<String>,
+}
+
+/// Resolve the executable name for `program` (`cargo`, `rustup`, ...), honoring an environment
+/// variable override (e.g. `CARGO=/path/to/cargo`, matching cargo's own convention) and falling
+/// back to the platform's usual executable name otherwise, since Windows shims expect `.exe`.
+fn resolve_exe(program: &str) -> PathBuf {
+    if let Some(path) = std::env::var_os(program.to_uppercase()) {
+        return PathBuf::from(path);
+    }
+    if cfg!(windows) {
+        PathBuf::from(format!("{program}.exe"))
+    } else {
+        PathBuf::from(program)
+    }
+}
+
+/// Build a `cargo` invocation, pinning `toolchain` via a leading `+<toolchain>` arg if given.
+fn cargo_command(toolchain: Option<&str>) -> Command {
+    let mut cmd = Command::new(resolve_exe("cargo"));
+    if let Some(toolchain) = toolchain {
+        cmd.arg(format!("+{toolchain}"));
+    }
+    cmd
+}
+
+/// Build a `rustup` invocation.
+fn rustup_command() -> Command {
+    Command::new(resolve_exe("rustup"))
+}
+
+/// Captured output of a failed command invocation. Modeled on clippy_dev's
+/// `CommandFailed(stdout, stderr)`: the child's streams are captured rather
+/// than inherited, so a threaded run can buffer them and print a single,
+/// non-interleaved report per directory instead of racing to the terminal.
+#[derive(Debug)]
+struct CommandFailed {
+    program: String,
+    stdout: String,
+    stderr: String,
+}
+
+impl std::fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "`{}` found errors", self.program)?;
+        if !self.stdout.trim().is_empty() {
+            writeln!(f, "--- stdout ---\n{}", self.stdout.trim_end())?;
+        }
+        if !self.stderr.trim().is_empty() {
+            writeln!(f, "--- stderr ---\n{}", self.stderr.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// Run `cmd` in `dir`, capturing stdout/stderr instead of inheriting them.
+fn exec(mut cmd: Command, dir: PathBuf, program: &str) -> Result<()> {
+    cmd.current_dir(&dir);
+    let out = cmd
+        .output()
+        .with_context(|| format!("failed to exec `{program}`"))?;
+    if !out.status.success() {
+        return Err(CommandFailed {
+            program: program.to_string(),
+            stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&out.stderr).into_owned(),
+        }
+        .into());
+    }
+    Ok(())
 }
 
 #[derive(Debug, Subcommand)]
@@ -46,6 +124,9 @@ enum Cmd {
         /// Comma-separated key=value config pairs for rustfmt
         #[clap(long)]
         config: Option<String>,
+        /// Verify formatting without rewriting files, failing if any file differs
+        #[clap(long)]
+        check: bool,
     },
     /// Run the cargo check hook
     Check {
@@ -57,32 +138,73 @@ enum Cmd {
         all_features: bool,
     },
     /// Run the cargo clippy hook
-    Clippy,
+    Clippy {
+        /// Comma-separated list of features to check
+        #[clap(long)]
+        features: Option<String>,
+        /// Activate all available features
+        #[clap(long)]
+        all_features: bool,
+        /// Check all targets (lib, bins, tests, examples, benches)
+        #[clap(long)]
+        all_targets: bool,
+        /// Lint the entire workspace instead of just the default package
+        #[clap(long)]
+        workspace: bool,
+        /// Lint(s) to deny, e.g. `--deny warnings`. Repeatable.
+        #[clap(long)]
+        deny: Vec<String>,
+        /// Lint(s) to warn on. Repeatable.
+        #[clap(long)]
+        warn: Vec<String>,
+        /// Lint(s) to allow. Repeatable.
+        #[clap(long)]
+        allow: Vec<String>,
+    },
+    /// Run the cargo test hook
+    Test {
+        /// Comma-separated list of features to test with
+        #[clap(long)]
+        features: Option<String>,
+        /// Activate all available features
+        #[clap(long)]
+        all_features: bool,
+    },
+    /// Run an arbitrary cargo subcommand, forwarding all given args as-is. Useful for wiring up
+    /// hooks this crate has no bespoke variant for, e.g. `cargo doc` or `cargo nextest`.
+    Custom {
+        /// An arg to forward to `cargo`, in order. Repeat for multiple, e.g.
+        /// `--arg doc --arg --no-deps` to run `cargo doc --no-deps`.
+        #[clap(long = "arg", allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
 }
 
 impl Cmd {
-    pub fn run(&self, dir: PathBuf) -> Result<()> {
+    pub fn run(&self, dir: PathBuf, toolchain: Option<&str>) -> Result<()> {
         match self {
-            Cmd::Fmt { config } => {
-                let mut cmd = Command::new("cargo");
+            Cmd::Fmt { config, check } => {
+                let mut cmd = cargo_command(toolchain);
                 cmd.arg("fmt");
 
-                if let Some(config) = config {
-                    cmd.args(["--", "--config", config]);
+                if *check || config.is_some() {
+                    cmd.arg("--");
+                    if *check {
+                        cmd.arg("--check");
+                    }
+                    if let Some(config) = config {
+                        cmd.args(["--config", config]);
+                    }
                 }
 
-                cmd.current_dir(dir);
-                let status = cmd.status().context("failed to exec `cargo fmt`")?;
-                if !status.success() {
-                    bail!("`cargo fmt` found errors");
-                }
-                Ok(())
+                let program = if *check { "cargo fmt --check" } else { "cargo fmt" };
+                exec(cmd, dir, program)
             }
             Cmd::Check {
                 features,
                 all_features,
             } => {
-                let mut cmd = Command::new("cargo");
+                let mut cmd = cargo_command(toolchain);
                 cmd.arg("check");
 
                 if *all_features {
@@ -91,36 +213,87 @@ impl Cmd {
                     cmd.args(["--features", features]);
                 }
 
-                cmd.current_dir(dir);
-                let status = cmd.status().context("failed to exec `cargo check`")?;
-                if !status.success() {
-                    bail!("`cargo check` found errors");
+                exec(cmd, dir, "cargo check")
+            }
+            Cmd::Clippy {
+                features,
+                all_features,
+                all_targets,
+                workspace,
+                deny,
+                warn,
+                allow,
+            } => {
+                let mut cmd = cargo_command(toolchain);
+                cmd.arg("clippy");
+
+                if *workspace {
+                    cmd.arg("--workspace");
                 }
-                Ok(())
+                if *all_targets {
+                    cmd.arg("--all-targets");
+                }
+                if *all_features {
+                    cmd.arg("--all-features");
+                } else if let Some(features) = features {
+                    cmd.args(["--features", features]);
+                }
+
+                cmd.arg("--");
+                if deny.is_empty() && warn.is_empty() && allow.is_empty() {
+                    cmd.args(["-D", "warnings"]);
+                } else {
+                    for lint in deny {
+                        cmd.args(["-D", lint]);
+                    }
+                    for lint in warn {
+                        cmd.args(["-W", lint]);
+                    }
+                    for lint in allow {
+                        cmd.args(["-A", lint]);
+                    }
+                }
+
+                exec(cmd, dir, "cargo clippy")
             }
-            Cmd::Clippy => {
-                let status = Command::new("cargo")
-                    .args(["clippy", "--", "-D", "warnings"])
-                    .current_dir(dir)
-                    .status()
-                    .context("failed to exec `cargo clippy`")?;
-                if !status.success() {
-                    bail!("`cargo clippy` found errors");
+            Cmd::Test {
+                features,
+                all_features,
+            } => {
+                let mut cmd = cargo_command(toolchain);
+                cmd.arg("test");
+
+                if *all_features {
+                    cmd.arg("--all-features");
+                } else if let Some(features) = features {
+                    cmd.args(["--features", features]);
                 }
-                Ok(())
+
+                exec(cmd, dir, "cargo test")
+            }
+            Cmd::Custom { args } => {
+                let mut cmd = cargo_command(toolchain);
+                cmd.args(args);
+
+                exec(cmd, dir, &format!("cargo {}", args.join(" ")))
             }
         }
     }
 
     /// Check the `cargo` subcommand can be run, validating `CargoOpts` are satisfied
-    pub fn check_subcommand(&self) -> Result<()> {
+    pub fn check_subcommand(&self, toolchain: Option<&str>) -> Result<()> {
         let sub = match self {
             Cmd::Fmt { .. } => "fmt",
             Cmd::Check { .. } => "check",
             Cmd::Clippy { .. } => "clippy",
+            Cmd::Test { .. } => "test",
+            Cmd::Custom { args } => match args.first() {
+                Some(sub) => sub.as_str(),
+                None => bail!("`cargo custom` requires at least one forwarded arg"),
+            },
         };
 
-        let out = Command::new("cargo")
+        let out = cargo_command(toolchain)
             .arg(sub)
             .arg("--help")
             .output()
@@ -144,13 +317,21 @@ impl Cmd {
             Cmd::Clippy { .. } => {
                 anyhow!("Missing `cargo clippy`, try installing with `rustup component add clippy`")
             }
+            Cmd::Test { .. } => {
+                anyhow!("Missing `cargo test`, you may need to update or reinstall rust.")
+            }
+            Cmd::Custom { args } => {
+                let sub = args.first().map(String::as_str).unwrap_or("<subcommand>");
+                anyhow!("Missing `cargo {sub}`, you may need to install it or update rust.")
+            }
         }
     }
 }
 
 /// Verify the cargo/rust toolchain exists and meets the configured requirements
 fn check_toolchain(opts: &CargoOpts) -> Result<()> {
-    match toolchain_version()? {
+    let toolchain = opts.toolchain.as_deref();
+    match toolchain_version(toolchain)? {
         Some(ver) => {
             if let Some(msrv) = &opts.rust_version {
                 if &ver < msrv {
@@ -175,8 +356,8 @@ fn check_toolchain(opts: &CargoOpts) -> Result<()> {
 
 /// Returns `Ok(None)` if cargo binary is not found / fails to run.
 /// Errors when `cargo --version` runs, but the output cannot be parsed.
-fn toolchain_version() -> Result<Option<Version>> {
-    let Ok(out) = Command::new("cargo").arg("--version").output() else { return Ok(None) };
+fn toolchain_version(toolchain: Option<&str>) -> Result<Option<Version>> {
+    let Ok(out) = cargo_command(toolchain).arg("--version").output() else { return Ok(None) };
     let stdout = String::from_utf8_lossy(&out.stdout);
     let version_re = Regex::new(r"cargo (\d+\.\d+\.\S+)").unwrap();
     let caps = version_re
@@ -189,7 +370,7 @@ fn toolchain_version() -> Result<Option<Version>> {
 }
 
 fn update_rust() -> Result<()> {
-    let status = Command::new("rustup")
+    let status = rustup_command()
         .arg("update")
         .status()
         .context("failed to run `rustup update`, is rust installed?")?;
@@ -199,27 +380,79 @@ fn update_rust() -> Result<()> {
     Ok(())
 }
 
+/// Run `cmd` across every dir in `run_dirs` concurrently, via a bounded pool
+/// of worker threads sized to the available parallelism. Each child's output
+/// is captured rather than inherited, so a failure is printed as a single,
+/// non-interleaved block under a header naming the directory it came from.
+/// Returns the number of directories that failed.
+fn run_hook_in_parallel(cmd: &Cmd, run_dirs: HashSet<PathBuf>, toolchain: Option<&str>) -> usize {
+    let queue = Mutex::new(run_dirs.into_iter());
+    let err_count = AtomicUsize::new(0);
+    let num_workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_workers {
+            scope.spawn(|| loop {
+                let Some(dir) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                if let Err(e) = cmd.run(dir.clone(), toolchain) {
+                    eprintln!("=== {} ===\n{e}", dir.display());
+                    err_count.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    err_count.into_inner()
+}
+
 /// Get all root cargo workspaces that need to be checked based on changed files
 fn get_run_dirs(changed_files: &[PathBuf]) -> HashSet<PathBuf> {
-    let root_dirs = find_cargo_root_dirs();
-    let mut run_dirs: HashSet<PathBuf> = HashSet::new();
     let current_dir = std::env::current_dir().unwrap();
+    let root_dirs = resolve_workspace_roots(find_cargo_root_dirs(), &current_dir);
+    let mut run_dirs: HashSet<PathBuf> = HashSet::new();
     for path in changed_files {
         if !is_rust_file(path) {
             continue;
         }
+        // `changed_files` are supplied by pre-commit relative to the repo root, while
+        // `root_dirs` are always absolute, so resolve `path` against `current_dir` first.
+        let abs_path = current_dir.join(path);
         if let Some(root) = root_dirs
             .iter()
-            .filter(|d| path.starts_with(d))
+            .filter(|d| abs_path.starts_with(d))
             .max_by_key(|path| path.components().count())
         {
-            run_dirs.insert(current_dir.join(root));
+            run_dirs.insert(root.clone());
         }
     }
     run_dirs
 }
 
-/// Find all root-level cargo workspaces from the current repository root
+/// Resolve each discovered manifest's true workspace root via [`workspace_root`]. Manifests are
+/// processed in order and skipped once their directory is already covered by a previously
+/// resolved root, so a workspace with N member crates only pays for one `cargo metadata` call
+/// instead of N. Returned roots are always absolute.
+fn resolve_workspace_roots(manifest_dirs: Vec<PathBuf>, current_dir: &Path) -> HashSet<PathBuf> {
+    let mut roots: HashSet<PathBuf> = HashSet::new();
+    for dir in manifest_dirs {
+        let abs_dir = current_dir.join(&dir);
+        if roots.iter().any(|root| abs_dir.starts_with(root)) {
+            continue;
+        }
+        let root = workspace_root(&dir.join("Cargo.toml"));
+        roots.insert(current_dir.join(root));
+    }
+    roots
+}
+
+/// Discover every cargo manifest in the repo. This is only the first pass of
+/// discovery: a manifest found here may belong to a workspace member rather
+/// than being a root in its own right, so callers should resolve it through
+/// [`workspace_root`] before treating its directory as something to run in.
 fn find_cargo_root_dirs() -> Vec<PathBuf> {
     let mut dirs = Vec::new();
     for entry in glob("**/Cargo.toml").unwrap() {
@@ -231,6 +464,33 @@ fn find_cargo_root_dirs() -> Vec<PathBuf> {
     dirs
 }
 
+/// Resolve the true workspace root directory for a given `Cargo.toml`, via `cargo metadata`.
+/// Falls back to the manifest's own (possibly relative) directory if `cargo metadata` is
+/// unavailable or fails; callers are responsible for making the result absolute.
+fn workspace_root(manifest_path: &Path) -> PathBuf {
+    let fallback = || manifest_path.parent().unwrap().to_path_buf();
+
+    let Ok(out) = cargo_command(None)
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .arg("--manifest-path")
+        .arg(manifest_path)
+        .output()
+    else {
+        return fallback();
+    };
+    if !out.status.success() {
+        return fallback();
+    }
+
+    let Ok(metadata) = serde_json::from_slice::<Value>(&out.stdout) else {
+        return fallback();
+    };
+    match metadata.get("workspace_root").and_then(Value::as_str) {
+        Some(root) => PathBuf::from(root),
+        None => fallback(),
+    }
+}
+
 /// Check if changed file path should trigger a hook run
 fn is_rust_file<P: AsRef<Path>>(path: P) -> bool {
     let path = path.as_ref();
@@ -250,28 +510,19 @@ fn is_rust_file<P: AsRef<Path>>(path: P) -> bool {
 
 fn main() -> ExitCode {
     let opts = Opts::parse();
+    let toolchain = opts.cargo_opts.toolchain.as_deref();
 
     if let Err(e) = check_toolchain(&opts.cargo_opts) {
         eprintln!("{e}");
         return ExitCode::FAILURE;
     }
-    if let Err(e) = opts.cmd.check_subcommand() {
+    if let Err(e) = opts.cmd.check_subcommand(toolchain) {
         eprintln!("{e}");
         return ExitCode::FAILURE;
     }
 
     let run_dirs = get_run_dirs(&opts.files);
-    let err_count = run_dirs
-        .into_iter()
-        .map(|dir| opts.cmd.run(dir))
-        .filter(|res| match res {
-            Ok(()) => false,
-            Err(e) => {
-                eprintln!("{}", e);
-                true
-            }
-        })
-        .count();
+    let err_count = run_hook_in_parallel(&opts.cmd, run_dirs, toolchain);
 
     if err_count > 0 {
         ExitCode::FAILURE
@@ -279,3 +530,50 @@ fn main() -> ExitCode {
         ExitCode::SUCCESS
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Restores the process's working directory on drop, so a failing assertion doesn't leave
+    /// the test binary's cwd pointed at a directory we're about to delete.
+    struct RestoreCwd(PathBuf);
+
+    impl Drop for RestoreCwd {
+        fn drop(&mut self) {
+            let _ = std::env::set_current_dir(&self.0);
+        }
+    }
+
+    #[test]
+    fn get_run_dirs_resolves_workspace_root_for_member_file() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let workspace_dir = std::env::temp_dir().join(format!("pre-commit-rust-test-{nanos}"));
+        let member_dir = workspace_dir.join("crate_a");
+        fs::create_dir_all(member_dir.join("src")).unwrap();
+        fs::write(
+            workspace_dir.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crate_a\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            member_dir.join("Cargo.toml"),
+            "[package]\nname = \"crate_a\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        fs::write(member_dir.join("src/lib.rs"), "").unwrap();
+
+        let _restore = RestoreCwd(std::env::current_dir().unwrap());
+        std::env::set_current_dir(&workspace_dir).unwrap();
+        let expected_root = std::env::current_dir().unwrap();
+
+        let run_dirs = get_run_dirs(&[PathBuf::from("crate_a/src/lib.rs")]);
+
+        assert_eq!(run_dirs, HashSet::from([expected_root]));
+
+        drop(_restore);
+        fs::remove_dir_all(&workspace_dir).ok();
+    }
+}